@@ -0,0 +1,274 @@
+use std::{
+    collections::{HashMap, VecDeque},
+    io::{BufRead, BufReader, Write},
+    os::unix::net::{UnixListener, UnixStream},
+    path::Path,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use anyhow::Context;
+
+use crate::shunt::ShuntCommand;
+
+const LOG_BACKLOG: usize = 200;
+
+/// Shared, addressable view of every command's running state. This is what
+/// lets the control socket act on a command by name without racing the
+/// supervisor thread that owns its `Handle` and `Child`. `stopped` is
+/// notified every time a command's pid is cleared, so callers (namely
+/// `restart`) can block until a command has actually exited instead of
+/// racing its supervisor loop.
+#[derive(Clone, Default)]
+pub struct Registry {
+    entries: Arc<Mutex<HashMap<String, Entry>>>,
+    stopped: Arc<Condvar>,
+}
+
+struct Entry {
+    config: ShuntCommand,
+    pid: Option<i32>,
+    /// Set by `try_reserve_start` between a control command deciding to
+    /// (re)spawn a command and its supervisor thread actually setting `pid`,
+    /// so a second concurrent `start`/`restart` can't slip in during that
+    /// window and spawn a duplicate instance.
+    starting: bool,
+    manual_stop: bool,
+    logs: VecDeque<String>,
+}
+
+/// Outcome of `Registry::try_reserve_start`.
+pub enum ReserveOutcome {
+    /// `name` was neither running nor already reserved; it's now reserved
+    /// and the caller should go ahead and spawn it.
+    Reserved,
+    AlreadyRunning,
+    NoSuchCommand,
+}
+
+impl Registry {
+    pub fn register(&self, name: &str, config: ShuntCommand) {
+        self.entries.lock().unwrap().insert(
+            name.to_owned(),
+            Entry {
+                config,
+                pid: None,
+                starting: false,
+                manual_stop: false,
+                logs: VecDeque::new(),
+            },
+        );
+    }
+
+    pub fn config_of(&self, name: &str) -> Option<ShuntCommand> {
+        self.entries
+            .lock()
+            .unwrap()
+            .get(name)
+            .map(|e| e.config.clone())
+    }
+
+    pub fn set_pid(&self, name: &str, pid: Option<i32>) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(e) = entries.get_mut(name) {
+            e.pid = pid;
+            e.starting = false;
+        }
+        if pid.is_none() {
+            // Wake anyone in `wait_until_stopped` for this (or any) command.
+            self.stopped.notify_all();
+        }
+    }
+
+    /// Atomically checks that `name` isn't already running or already
+    /// reserved by a concurrent `start`/`restart`, and if so reserves it.
+    /// Checking and reserving under the same lock closes the race a plain
+    /// `is_running` check followed by a separate spawn would leave open:
+    /// two callers both observing "not running" and both spawning. The
+    /// reservation is cleared by the next `set_pid` call for `name`, whether
+    /// that's the new instance actually starting or its spawn failing.
+    pub fn try_reserve_start(&self, name: &str) -> ReserveOutcome {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get_mut(name) {
+            None => ReserveOutcome::NoSuchCommand,
+            Some(e) if e.pid.is_some() || e.starting => ReserveOutcome::AlreadyRunning,
+            Some(e) => {
+                e.starting = true;
+                ReserveOutcome::Reserved
+            }
+        }
+    }
+
+    /// Blocks until `name` has no live pid (or doesn't exist). Used by
+    /// `restart` so it doesn't respawn a command while its old instance is
+    /// still alive and racing the new one for the same resources.
+    pub fn wait_until_stopped(&self, name: &str) {
+        let entries = self.entries.lock().unwrap();
+        let _ = self
+            .stopped
+            .wait_while(entries, |entries| {
+                entries.get(name).map_or(false, |e| e.pid.is_some())
+            })
+            .unwrap();
+    }
+
+    pub fn push_log(&self, name: &str, line: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(e) = entries.get_mut(name) {
+            if e.logs.len() >= LOG_BACKLOG {
+                e.logs.pop_front();
+            }
+            e.logs.push_back(line.to_owned());
+        }
+    }
+
+    /// Returns whether the command was stopped through the control socket
+    /// since it was last started, clearing the flag. The supervisor checks
+    /// this after a child exits to decide whether a manual stop should
+    /// override the restart policy.
+    pub fn take_manual_stop(&self, name: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        entries
+            .get_mut(name)
+            .map_or(false, |e| std::mem::take(&mut e.manual_stop))
+    }
+
+    fn list(&self) -> Vec<(String, bool)> {
+        let entries = self.entries.lock().unwrap();
+        let mut names: Vec<_> = entries
+            .iter()
+            .map(|(name, e)| (name.clone(), e.pid.is_some()))
+            .collect();
+        names.sort();
+        names
+    }
+
+    fn stop(&self, name: &str) -> anyhow::Result<()> {
+        let mut entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get_mut(name)
+            .with_context(|| format!("no such command \"{name}\""))?;
+        entry.manual_stop = true;
+        if let Some(pid) = entry.pid {
+            nix::sys::signal::kill(nix::unistd::Pid::from_raw(pid), nix::sys::signal::Signal::SIGTERM)
+                .context("could not signal process")?;
+        }
+        Ok(())
+    }
+
+    fn logs(&self, name: &str) -> anyhow::Result<Vec<String>> {
+        let entries = self.entries.lock().unwrap();
+        let entry = entries
+            .get(name)
+            .with_context(|| format!("no such command \"{name}\""))?;
+        Ok(entry.logs.iter().cloned().collect())
+    }
+}
+
+/// Opens the control socket at `path` and serves `list`/`stop`/`start`/
+/// `restart`/`logs` requests line-by-line until the process exits. `start`
+/// re-spawns a command's supervisor loop; the caller supplies it since doing
+/// so needs the dependency readiness and jobserver state `control` doesn't
+/// otherwise know about.
+pub fn serve(
+    path: &Path,
+    registry: Registry,
+    start: impl Fn(&str) + Send + Sync + 'static,
+) -> anyhow::Result<()> {
+    let _ = std::fs::remove_file(path);
+    let listener = UnixListener::bind(path).context("could not bind control socket")?;
+    let start = Arc::new(start);
+
+    for stream in listener.incoming() {
+        let stream = stream.context("control socket accept failed")?;
+        let registry = registry.clone();
+        let start = Arc::clone(&start);
+        std::thread::spawn(move || {
+            if let Err(e) = handle_connection(stream, &registry, start.as_ref()) {
+                eprintln!("control connection error: {e:?}");
+            }
+        });
+    }
+
+    Ok(())
+}
+
+fn handle_connection(
+    stream: UnixStream,
+    registry: &Registry,
+    start: &(dyn Fn(&str) + Send + Sync),
+) -> anyhow::Result<()> {
+    let mut writer = stream.try_clone().context("could not clone control socket")?;
+    let reader = BufReader::new(stream);
+
+    for line in reader.lines() {
+        let line = line.context("could not read control command")?;
+        let response = dispatch(&line, registry, start);
+        writeln!(writer, "{response}").context("could not write control response")?;
+    }
+
+    Ok(())
+}
+
+fn dispatch(line: &str, registry: &Registry, start: &(dyn Fn(&str) + Send + Sync)) -> String {
+    let mut parts = line.split_whitespace();
+
+    match (parts.next(), parts.next()) {
+        (Some("list"), _) => registry
+            .list()
+            .into_iter()
+            .map(|(name, running)| {
+                format!("{name}\t{}", if running { "running" } else { "stopped" })
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        (Some("stop"), Some(name)) => match registry.stop(name) {
+            Ok(()) => format!("stopped {name}"),
+            Err(e) => format!("error: {e:?}"),
+        },
+        (Some("start"), Some(name)) => match registry.try_reserve_start(name) {
+            ReserveOutcome::Reserved => {
+                start(name);
+                format!("starting {name}")
+            }
+            ReserveOutcome::AlreadyRunning => format!("error: \"{name}\" is already running"),
+            ReserveOutcome::NoSuchCommand => format!("error: no such command \"{name}\""),
+        },
+        (Some("restart"), Some(name)) => {
+            let _ = registry.stop(name);
+            registry.wait_until_stopped(name);
+            match registry.try_reserve_start(name) {
+                ReserveOutcome::Reserved => {
+                    start(name);
+                    format!("restarting {name}")
+                }
+                ReserveOutcome::AlreadyRunning => format!("error: \"{name}\" is already running"),
+                ReserveOutcome::NoSuchCommand => format!("error: no such command \"{name}\""),
+            }
+        }
+        (Some("logs"), Some(name)) => match registry.logs(name) {
+            Ok(lines) => lines.join("\n"),
+            Err(e) => format!("error: {e:?}"),
+        },
+        (Some("stop" | "start" | "restart" | "logs"), None) => {
+            "error: expected a command name".to_owned()
+        }
+        (Some(other), _) => format!("error: unknown command \"{other}\""),
+        (None, _) => "error: empty command".to_owned(),
+    }
+}
+
+/// The `shunt ctl` client: sends one line to the control socket and prints
+/// whatever comes back, so `shunt ctl sock.sock stop web` works from another
+/// terminal.
+pub fn run_client(socket: &Path, args: &[String]) -> anyhow::Result<()> {
+    let mut stream = UnixStream::connect(socket).context("could not connect to control socket")?;
+    writeln!(stream, "{}", args.join(" ")).context("could not send control command")?;
+    stream.shutdown(std::net::Shutdown::Write).ok();
+
+    let reader = BufReader::new(stream);
+    for line in reader.lines() {
+        println!("{}", line.context("could not read control response")?);
+    }
+
+    Ok(())
+}
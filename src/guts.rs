@@ -1,33 +1,79 @@
 use std::{
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fs::File,
     io::{BufRead, BufReader, Read, Write},
-    os::fd::{AsRawFd, FromRawFd},
+    net::TcpStream,
+    os::{
+        fd::{AsRawFd, FromRawFd, RawFd},
+        unix::process::CommandExt,
+    },
+    path::{Path, PathBuf},
     process::{Child, Command, Stdio},
-    sync::atomic::{self, AtomicU64},
+    sync::{
+        atomic::{self, AtomicU64},
+        Arc, Condvar, Mutex,
+    },
+    time::{Duration, Instant},
 };
 
 use anyhow::Context;
 use nix::libc::{SIGINT, SIGTERM};
+use regex::Regex;
 use signal_hook::iterator::Signals;
-use termcolor::{Color, ColorChoice, ColorSpec, StandardStream, StandardStreamLock, WriteColor};
+use termcolor::{Color, ColorSpec};
 
-use crate::shunt::{AutoBool, Shunt, ShuntCommand};
+use crate::control::Registry;
+use crate::output::{self, CommandInfo, EventSink, Stream};
+use crate::shunt::{
+    AutoBool, Backoff, Compression, Isolate, LogFile, ReadyWhen, RestartPolicy, Shunt, ShuntCommand,
+};
 
-#[derive(Debug, Clone)]
-struct CommandInfo {
-    name: String,
-    color: Option<ColorSpec>,
+/// A process is considered to have "settled" once it's stayed up this long;
+/// crossing this resets the backoff attempt counter.
+const RESTART_STABLE_AFTER: Duration = Duration::from_secs(60);
+
+/// Tracks which commands have signalled readiness so that dependants know
+/// when it's safe to start.
+#[derive(Default)]
+struct Readiness {
+    ready: Mutex<HashSet<String>>,
+    cond: Condvar,
 }
 
-fn handle_output(info: &CommandInfo, out: impl Read) {
-    let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+impl Readiness {
+    fn wait_for(&self, deps: &[String]) {
+        let mut ready = self.ready.lock().unwrap();
+        while !deps.iter().all(|dep| ready.contains(dep)) {
+            ready = self.cond.wait(ready).unwrap();
+        }
+    }
+
+    fn mark_ready(&self, name: &str) {
+        let mut ready = self.ready.lock().unwrap();
+        if ready.insert(name.to_owned()) {
+            self.cond.notify_all();
+        }
+    }
+}
 
+fn handle_output(
+    info: &CommandInfo,
+    out: impl Read,
+    mut on_line: impl FnMut(&str),
+    mut log: Option<LogWriter>,
+    sink: &Arc<dyn EventSink>,
+) {
     let br = BufReader::new(out);
 
     for line in br.lines() {
         match line {
-            Ok(line) => prefix_write(&mut stdout, info, &line),
+            Ok(line) => {
+                on_line(&line);
+                sink.line(info, Stream::Stdout, &line);
+                if let Some(log) = &mut log {
+                    log.write_line(&line);
+                }
+            }
             Err(_) => {
                 // This is the expected way to exit, the output we're reading
                 // got closed.
@@ -37,76 +83,809 @@ fn handle_output(info: &CommandInfo, out: impl Read) {
     }
 }
 
+/// Tees a command's merged output to `config.path`, rotating the active file
+/// once it passes `max_size` and keeping at most `max_files` rotated
+/// segments. Rotation is checked once per line rather than per byte, so the
+/// active file can briefly overshoot `max_size` by up to one line.
+struct LogWriter {
+    name: String,
+    sink: Arc<dyn EventSink>,
+    path: PathBuf,
+    max_size: u64,
+    max_files: u32,
+    compress: Compression,
+    file: File,
+    written: u64,
+}
+
+impl LogWriter {
+    fn open(name: &str, sink: &Arc<dyn EventSink>, config: &LogFile) -> anyhow::Result<Self> {
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&config.path)
+            .context("could not open log file")?;
+        let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+
+        Ok(LogWriter {
+            name: name.to_owned(),
+            sink: Arc::clone(sink),
+            path: config.path.clone(),
+            max_size: config.max_size,
+            max_files: config.max_files,
+            compress: config.compress,
+            file,
+            written,
+        })
+    }
+
+    fn write_line(&mut self, line: &str) {
+        if self.written >= self.max_size {
+            self.rotate();
+        }
+
+        if writeln!(self.file, "{line}").is_ok() {
+            self.written += line.len() as u64 + 1;
+        }
+    }
+
+    fn rotated_path(&self, n: u32) -> PathBuf {
+        PathBuf::from(format!("{}.{n}", self.path.display()))
+    }
+
+    fn rotate(&mut self) {
+        let suffix = compression_suffix(self.compress);
+        let compressed = |p: &Path| PathBuf::from(format!("{}{suffix}", p.display()));
+
+        let _ = std::fs::remove_file(self.rotated_path(self.max_files));
+        let _ = std::fs::remove_file(compressed(&self.rotated_path(self.max_files)));
+
+        for n in (1..self.max_files).rev() {
+            let from = self.rotated_path(n);
+            let to = self.rotated_path(n + 1);
+            if from.exists() {
+                let _ = std::fs::rename(&from, &to);
+            }
+            let (from_gz, to_gz) = (compressed(&from), compressed(&to));
+            if from_gz.exists() {
+                let _ = std::fs::rename(&from_gz, &to_gz);
+            }
+        }
+
+        let rotated = self.rotated_path(1);
+        if let Err(e) = std::fs::rename(&self.path, &rotated) {
+            self.sink.message(
+                Some(&self.name),
+                &format!("could not rotate log file {:?}: {e}", self.path),
+            );
+            return;
+        }
+
+        if self.compress != Compression::None {
+            let compress = self.compress;
+            let name = self.name.clone();
+            let sink = Arc::clone(&self.sink);
+            std::thread::spawn(move || compress_rotated_log(&name, &sink, &rotated, compress));
+        }
+
+        match std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+        {
+            Ok(file) => {
+                self.file = file;
+                self.written = 0;
+            }
+            Err(e) => self.sink.message(
+                Some(&self.name),
+                &format!("could not reopen log file {:?}: {e}", self.path),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod rotate_tests {
+    use super::*;
+
+    struct NullSink;
+
+    impl EventSink for NullSink {
+        fn line(&self, _info: &CommandInfo, _stream: Stream, _text: &str) {}
+        fn exit(&self, _command: &str, _detail: &str, _code: Option<i32>, _success: bool) {}
+        fn restarting(&self, _info: &CommandInfo, _attempt: u32) {}
+        fn message(&self, _command: Option<&str>, _text: &str) {}
+    }
+
+    fn temp_log_path(tag: &str) -> PathBuf {
+        static COUNTER: AtomicU64 = AtomicU64::new(0);
+        let n = COUNTER.fetch_add(1, atomic::Ordering::Relaxed);
+        std::env::temp_dir().join(format!("shunt-rotate-test-{}-{tag}-{n}.log", std::process::id()))
+    }
+
+    fn writer(path: &Path, max_files: u32) -> LogWriter {
+        LogWriter::open(
+            "test",
+            &(Arc::new(NullSink) as Arc<dyn EventSink>),
+            &LogFile {
+                path: path.to_owned(),
+                max_size: u64::MAX,
+                max_files,
+                compress: Compression::None,
+            },
+        )
+        .unwrap()
+    }
+
+    fn cleanup(path: &Path, max_files: u32) {
+        let _ = std::fs::remove_file(path);
+        for n in 1..=max_files {
+            let _ = std::fs::remove_file(PathBuf::from(format!("{}.{n}", path.display())));
+        }
+    }
+
+    #[test]
+    fn rotate_shifts_existing_segments_up_by_one() {
+        let path = temp_log_path("shift");
+        let mut w = writer(&path, 3);
+        w.write_line("current");
+
+        std::fs::write(format!("{}.1", path.display()), "newest-of-the-rotated").unwrap();
+        std::fs::write(format!("{}.2", path.display()), "middle").unwrap();
+
+        w.rotate();
+
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.1", path.display())).unwrap(),
+            "current\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.2", path.display())).unwrap(),
+            "newest-of-the-rotated"
+        );
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.3", path.display())).unwrap(),
+            "middle"
+        );
+
+        cleanup(&path, 3);
+    }
+
+    #[test]
+    fn rotate_drops_the_segment_beyond_max_files() {
+        let path = temp_log_path("drop");
+        let mut w = writer(&path, 2);
+        w.write_line("current");
+
+        std::fs::write(format!("{}.1", path.display()), "will become .2").unwrap();
+        std::fs::write(format!("{}.2", path.display()), "should be discarded").unwrap();
+
+        w.rotate();
+
+        assert_eq!(
+            std::fs::read_to_string(format!("{}.2", path.display())).unwrap(),
+            "will become .2"
+        );
+
+        cleanup(&path, 2);
+    }
+
+    #[test]
+    fn rotate_resets_written_and_reopens_the_active_file() {
+        let path = temp_log_path("reset");
+        let mut w = writer(&path, 1);
+        w.write_line("some fairly long line to bump `written`");
+        assert!(w.written > 0);
+
+        w.rotate();
+
+        assert_eq!(w.written, 0);
+        w.write_line("after rotation");
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "after rotation\n");
+
+        cleanup(&path, 1);
+    }
+}
+
+fn compression_suffix(compress: Compression) -> &'static str {
+    match compress {
+        Compression::None => "",
+        Compression::Gzip => ".gz",
+        Compression::Bzip2 => ".bz2",
+    }
+}
+
+/// Compresses a freshly-rotated log segment on a background thread and
+/// removes the uncompressed copy, so rotation itself never blocks on it.
+fn compress_rotated_log(name: &str, sink: &Arc<dyn EventSink>, path: &Path, compress: Compression) {
+    let dest = PathBuf::from(format!("{}{}", path.display(), compression_suffix(compress)));
+
+    let result = (|| -> anyhow::Result<()> {
+        let mut input = File::open(path).context("could not open rotated log for compression")?;
+        let output = File::create(&dest).context("could not create compressed log file")?;
+
+        match compress {
+            Compression::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(output, flate2::Compression::default());
+                std::io::copy(&mut input, &mut encoder).context("could not gzip rotated log")?;
+                encoder.finish().context("could not finish gzip stream")?;
+            }
+            Compression::Bzip2 => {
+                let mut encoder = bzip2::write::BzEncoder::new(output, bzip2::Compression::default());
+                std::io::copy(&mut input, &mut encoder).context("could not bzip2 rotated log")?;
+                encoder.finish().context("could not finish bzip2 stream")?;
+            }
+            Compression::None => unreachable!("compress_rotated_log called without compression"),
+        }
+
+        Ok(())
+    })();
+
+    match result {
+        Ok(()) => {
+            let _ = std::fs::remove_file(path);
+        }
+        Err(e) => sink.message(Some(name), &format!("could not compress log file {path:?}: {e:?}")),
+    }
+}
+
+/// Blocks until a localhost TCP connection to `port` succeeds, then marks
+/// `name` ready. Polls with a capped exponential backoff so a slow-starting
+/// server doesn't get hammered. `exited` is set by the caller once the child
+/// has exited, so a command that crashes before ever listening doesn't poll
+/// forever and hang `supervise`'s `thread::scope`.
+fn watch_port(name: &str, port: u16, readiness: &Readiness, exited: &atomic::AtomicBool) {
+    let mut backoff = Duration::from_millis(50);
+    loop {
+        if TcpStream::connect(("127.0.0.1", port)).is_ok() {
+            readiness.mark_ready(name);
+            return;
+        }
+        if exited.load(atomic::Ordering::SeqCst) {
+            return;
+        }
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(Duration::from_secs(2));
+    }
+}
+
 pub fn go(config: Shunt) -> anyhow::Result<()> {
     let mut signals = Signals::new([SIGTERM, SIGINT])?;
     let handle = signals.handle();
 
-    let mut handles = config
-        .commands
-        .iter()
-        .map(|(name, info)| start_command(name, info))
-        .collect::<Vec<_>>();
+    config.validate_ready_when().context("invalid command configuration")?;
+
+    let order = config
+        .dependency_order()
+        .context("invalid command dependency graph")?;
+
+    let sink: Arc<dyn EventSink> = Arc::from(output::sink_for(config.output));
+
+    {
+        let sink = Arc::clone(&sink);
+        std::thread::spawn(move || {
+            for signal in &mut signals {
+                sink.message(None, &format!("shunt received signal {}", signal));
+            }
+        });
+    }
+
+    let readiness = Arc::new(Readiness::default());
+    let jobserver = config
+        .concurrency
+        .map(Jobserver::new)
+        .transpose()?
+        .map(Arc::new);
+    let registry = Registry::default();
+
+    for name in &order {
+        registry.register(name, config.commands[name].clone());
+    }
+
+    if let Some(control_path) = config.control.clone() {
+        let registry = registry.clone();
+        let readiness = Arc::clone(&readiness);
+        let jobserver = jobserver.clone();
+        let sink = Arc::clone(&sink);
+
+        std::thread::spawn(move || {
+            let start = {
+                let registry = registry.clone();
+                move |name: &str| respawn(name, &registry, &readiness, &jobserver, &sink)
+            };
+            if let Err(e) = crate::control::serve(&control_path, registry, start) {
+                eprintln!("control socket error: {e:?}");
+            }
+        });
+    }
+
+    let mut joins = Vec::new();
+    for name in order {
+        let cmd_config = config.commands[&name].clone();
+        let readiness = Arc::clone(&readiness);
+        let jobserver = jobserver.clone();
+        let registry = registry.clone();
+        let sink = Arc::clone(&sink);
+
+        joins.push(std::thread::spawn(move || {
+            readiness.wait_for(&cmd_config.depends_on);
+            supervise(
+                &name,
+                &cmd_config,
+                &readiness,
+                jobserver.as_deref(),
+                &registry,
+                &sink,
+            );
+        }));
+    }
+
+    for join in joins {
+        let _ = join.join();
+    }
+
+    handle.close();
+    Ok(())
+}
+
+/// Starts (or restarts) a command's supervisor loop on demand. Used by the
+/// control socket's `start`/`restart` handlers, which can fire at any point
+/// after startup, so — unlike the initial fleet — it's spawned detached
+/// rather than joined by `go`.
+fn respawn(
+    name: &str,
+    registry: &Registry,
+    readiness: &Arc<Readiness>,
+    jobserver: &Option<Arc<Jobserver>>,
+    sink: &Arc<dyn EventSink>,
+) {
+    let Some(cmd_config) = registry.config_of(name) else {
+        sink.message(None, &format!("control: no such command \"{name}\""));
+        return;
+    };
+
+    let readiness = Arc::clone(readiness);
+    let jobserver = jobserver.clone();
+    let registry = registry.clone();
+    let sink = Arc::clone(sink);
+    let name = name.to_owned();
 
     std::thread::spawn(move || {
-        for signal in &mut signals {
-            println!("shunt received signal {}", signal);
-        }
+        readiness.wait_for(&cmd_config.depends_on);
+        supervise(
+            &name,
+            &cmd_config,
+            &readiness,
+            jobserver.as_deref(),
+            &registry,
+            &sink,
+        );
     });
+}
 
-    std::thread::scope(|s| {
-        for h in &mut handles {
-            let h = match h {
-                Ok(h) => h,
-                Err(e) => {
-                    eprintln!("{e:?}");
-                    continue;
+/// A GNU make jobserver: an anonymous pipe pre-filled with `concurrency - 1`
+/// one-byte tokens. A command beyond the first must take a token before
+/// starting and must give it back when done, and `MAKEFLAGS` is exported so
+/// recursive `make`/`cargo` invocations draw from the same pool.
+struct Jobserver {
+    read_fd: i32,
+    write_fd: i32,
+    first: atomic::AtomicBool,
+    makeflags: String,
+}
+
+impl Jobserver {
+    fn new(concurrency: u32) -> anyhow::Result<Self> {
+        let mut fds = [-1i32; 2];
+        if unsafe { nix::libc::pipe(&mut fds[0]) } != 0 {
+            anyhow::bail!("could not create jobserver pipe");
+        }
+        let (read_fd, write_fd) = (fds[0], fds[1]);
+
+        for _ in 0..concurrency.saturating_sub(1) {
+            let token = b'+';
+            if unsafe { nix::libc::write(write_fd, &token as *const u8 as *const _, 1) } != 1 {
+                anyhow::bail!("could not prime jobserver token");
+            }
+        }
+
+        Ok(Jobserver {
+            read_fd,
+            write_fd,
+            first: atomic::AtomicBool::new(true),
+            makeflags: format!("--jobserver-auth={read_fd},{write_fd}"),
+        })
+    }
+
+    /// Blocks until `weight` tokens are available, except that the very
+    /// first caller gets one implicit free token — this avoids the classic
+    /// jobserver deadlock where nothing has put tokens back yet. Only one
+    /// unit of `weight` is ever exempted this way; the rest is still drawn
+    /// from the pool, so a first caller with `weight > 1` can't push
+    /// concurrency above `concurrency` by more than that single free token.
+    /// Returns how many tokens were actually drawn from the pool, which the
+    /// caller must pass back to `release` — it's not always `weight`.
+    fn acquire(&self, weight: u32) -> u32 {
+        let to_acquire = if self.first.swap(false, atomic::Ordering::SeqCst) {
+            weight.saturating_sub(1)
+        } else {
+            weight
+        };
+        for _ in 0..to_acquire {
+            let mut byte = [0u8; 1];
+            unsafe { nix::libc::read(self.read_fd, byte.as_mut_ptr() as *mut _, 1) };
+        }
+        to_acquire
+    }
+
+    fn release(&self, weight: u32) {
+        for _ in 0..weight {
+            let token = b'+';
+            unsafe { nix::libc::write(self.write_fd, &token as *const u8 as *const _, 1) };
+        }
+    }
+}
+
+/// Starts `name`, waits for it to exit, and — depending on `cmd_config.restart`
+/// — relaunches it with capped exponential backoff. The command keeps the
+/// same `CommandInfo` (and so the same color) across restarts. `registry` is
+/// kept up to date with the live pid and recent output so the control
+/// socket can act on the command, and a manual `stop` through the registry
+/// overrides the restart policy.
+fn supervise(
+    name: &str,
+    cmd_config: &ShuntCommand,
+    readiness: &Readiness,
+    jobserver: Option<&Jobserver>,
+    registry: &Registry,
+    sink: &Arc<dyn EventSink>,
+) {
+    let info = CommandInfo {
+        name: name.to_owned(),
+        color: command_color(name, cmd_config),
+    };
+
+    let mut attempt = 0u32;
+
+    loop {
+        let acquired = jobserver.map_or(0, |js| js.acquire(cmd_config.weight));
+
+        let mut h = match start_command(&info, cmd_config, jobserver, sink) {
+            Ok(h) => h,
+            Err(e) => {
+                sink.message(Some(name), &format!("{e:?}"));
+                if let Some(js) = jobserver {
+                    js.release(acquired);
                 }
-            };
+                // Nothing ever got a chance to set_pid(Some(..)), but a
+                // control-socket start/restart may be waiting on this
+                // command's reservation (see Registry::try_reserve_start) —
+                // clear it the same way a normal exit would.
+                registry.set_pid(name, None);
+                return;
+            }
+        };
+
+        registry.set_pid(name, Some(h.child.id() as i32));
+
+        let tty_out = h.tty_master.try_clone().unwrap();
+        let ready_when = cmd_config.ready_when.clone().unwrap_or(ReadyWhen::Exit);
+        let started_at = Instant::now();
+
+        let succeeded = if let ReadyWhen::Port { port } = ready_when {
+            let mut succeeded = false;
+            let exited = atomic::AtomicBool::new(false);
+            std::thread::scope(|s2| {
+                s2.spawn(|| watch_port(name, port, readiness, &exited));
+                succeeded = run_command(
+                    &mut h,
+                    &info,
+                    tty_out,
+                    name,
+                    &ready_when,
+                    cmd_config.log.as_ref(),
+                    readiness,
+                    registry,
+                    sink,
+                );
+                exited.store(true, atomic::Ordering::SeqCst);
+            });
+            succeeded
+        } else {
+            run_command(
+                &mut h,
+                &info,
+                tty_out,
+                name,
+                &ready_when,
+                cmd_config.log.as_ref(),
+                readiness,
+                registry,
+                sink,
+            )
+        };
+
+        registry.set_pid(name, None);
+
+        if let Some(js) = jobserver {
+            js.release(acquired);
+        }
+
+        if started_at.elapsed() > RESTART_STABLE_AFTER {
+            attempt = 0;
+        }
+
+        if registry.take_manual_stop(name) {
+            return;
+        }
+
+        if !should_restart(cmd_config.restart, succeeded, attempt, cmd_config.max_retries) {
+            return;
+        }
+
+        attempt += 1;
+        let delay = backoff_delay(&cmd_config.backoff, attempt);
+        sink.restarting(&info, attempt);
+        std::thread::sleep(delay);
+    }
+}
+
+fn should_restart(
+    policy: RestartPolicy,
+    succeeded: bool,
+    attempt: u32,
+    max_retries: Option<u32>,
+) -> bool {
+    let wants_restart = match policy {
+        RestartPolicy::Never => false,
+        RestartPolicy::OnFailure => !succeeded,
+        RestartPolicy::Always => true,
+    };
+
+    wants_restart && max_retries.map_or(true, |max| attempt < max)
+}
 
-            // if let Some(tty_master) = h.tty_master.take() {
-            let info = h.info.clone();
-            let tty = h.tty_master.try_clone().unwrap();
+fn backoff_delay(backoff: &Backoff, attempt: u32) -> Duration {
+    let factor = 1u64.checked_shl(attempt.saturating_sub(1)).unwrap_or(u64::MAX);
+    let millis = backoff.base_ms.saturating_mul(factor).min(backoff.ceiling_ms);
+    Duration::from_millis(millis)
+}
 
-            s.spawn(move || handle_wait(h));
-            s.spawn(move || handle_output(&info, tty));
+fn run_command(
+    h: &mut Handle,
+    info: &CommandInfo,
+    tty_out: File,
+    name: &str,
+    ready_when: &ReadyWhen,
+    log_config: Option<&LogFile>,
+    readiness: &Readiness,
+    registry: &Registry,
+    sink: &Arc<dyn EventSink>,
+) -> bool {
+    let pattern = match ready_when {
+        ReadyWhen::LogMatch { pattern } => {
+            Some(Regex::new(pattern).expect("pattern already validated by Shunt::validate_ready_when"))
         }
+        _ => None,
+    };
+
+    let mut succeeded = false;
+
+    std::thread::scope(|s| {
+        s.spawn(|| {
+            succeeded = handle_wait(h, sink);
+            if matches!(ready_when, ReadyWhen::Exit) && succeeded {
+                readiness.mark_ready(name);
+            }
+        });
+        s.spawn(|| {
+            let log = log_config.and_then(|config| match LogWriter::open(name, sink, config) {
+                Ok(writer) => Some(writer),
+                Err(e) => {
+                    sink.message(Some(name), &format!("could not open log file: {e:?}"));
+                    None
+                }
+            });
+
+            handle_output(
+                info,
+                tty_out,
+                |line| {
+                    registry.push_log(name, line);
+                    if let Some(re) = &pattern {
+                        if re.is_match(line) {
+                            readiness.mark_ready(name);
+                        }
+                    }
+                },
+                log,
+                sink,
+            );
+        });
     });
-    handle.close();
-    Ok(())
+
+    succeeded
 }
 
-fn handle_wait(h: &mut Handle) {
+/// Waits for the child to exit, emitting its outcome through `sink`. Returns
+/// whether it exited successfully.
+fn handle_wait(h: &mut Handle, sink: &Arc<dyn EventSink>) -> bool {
     match h.child.wait() {
         Ok(status) => {
-            println!("{} finished: {}", h.info.name, status);
+            sink.exit(
+                &h.info.name,
+                &format!("finished: {status}"),
+                status.code(),
+                status.success(),
+            );
+            status.success()
+        }
+        Err(e) => {
+            sink.exit(
+                &h.info.name,
+                &format!("failed to be waited on: {e}"),
+                None,
+                false,
+            );
+            false
         }
-        Err(e) => println!("{} failed to be waited on: {}", h.info.name, e),
     }
 }
 
-fn colored_write(stdout: &mut StandardStreamLock, color: &Option<ColorSpec>, s: &str) {
-    if let Some(color) = color {
-        stdout.set_color(color).unwrap();
+fn make_color(c: Color) -> ColorSpec {
+    let mut col = ColorSpec::new();
+    col.set_fg(Some(c));
+    col
+}
+
+/// Pre-built, already-heap-allocated arguments for remounting `/proc`, so the
+/// `pre_exec` hook (which must not allocate, see `unshare_namespaces`) only
+/// ever dereferences pointers it was handed.
+struct ProcMount {
+    source: std::ffi::CString,
+    target: std::ffi::CString,
+    fstype: std::ffi::CString,
+}
+
+impl ProcMount {
+    fn new() -> Self {
+        ProcMount {
+            source: std::ffi::CString::new("proc").unwrap(),
+            target: std::ffi::CString::new("/proc").unwrap(),
+            fstype: std::ffi::CString::new("proc").unwrap(),
+        }
     }
-    write!(stdout, "{}", s).unwrap();
-    if color.is_some() {
-        stdout.reset().unwrap();
+}
+
+/// Writes `errno` to `fd` as raw bytes. No formatting, no locking, no
+/// allocation — safe to call from `pre_exec`, unlike `eprintln!`.
+fn report_errno(fd: RawFd, errno: i32) {
+    let bytes = errno.to_ne_bytes();
+    unsafe {
+        nix::libc::write(fd, bytes.as_ptr() as *const _, bytes.len());
     }
 }
 
-fn prefix_write(stream: &mut StandardStream, info: &CommandInfo, s: &str) {
-    let mut stream = stream.lock();
-    colored_write(&mut stream, &info.color, &format!("[{}] ", &info.name));
-    writeln!(&mut stream, "{}", s).unwrap();
+/// Runs in the forked child, before exec, and unshares whichever namespaces
+/// `isolate` opted into. This is `pre_exec`'d into a process that may have
+/// been forked from a thread mid-allocation or mid-`prefix_write` elsewhere
+/// in shunt, so everything here must be async-signal-safe: no heap
+/// allocation, no locking, no `eprintln!`. A failed `unshare` or `fork` is
+/// reported back to the parent over `report_fd` as a raw errno instead, which
+/// the parent logs through the normal `EventSink` machinery once it's safe to
+/// do so. A failed `/proc` remount is not checked or reported.
+///
+/// `unshare(CLONE_NEWPID)` only moves a process's *subsequent children* into
+/// the new PID namespace, not the caller itself (pid_namespaces(7)), so
+/// `isolate.pid` forks again here: the grandchild becomes PID 1 of the new
+/// namespace and falls through to let `Command` exec the real command, while
+/// this process blocks on it and mirrors its exit status, since it's the pid
+/// `Command::spawn`'s caller is actually waiting on.
+fn unshare_namespaces(isolate: &Isolate, proc_mount: Option<&ProcMount>, report_fd: RawFd) {
+    let mut flags = nix::sched::CloneFlags::empty();
+    if isolate.pid {
+        flags |= nix::sched::CloneFlags::CLONE_NEWPID;
+    }
+    if isolate.mount {
+        flags |= nix::sched::CloneFlags::CLONE_NEWNS;
+    }
+    if isolate.net {
+        flags |= nix::sched::CloneFlags::CLONE_NEWNET;
+    }
+    if isolate.uts {
+        flags |= nix::sched::CloneFlags::CLONE_NEWUTS;
+    }
+
+    if flags.is_empty() {
+        return;
+    }
+
+    if let Err(e) = nix::sched::unshare(flags) {
+        report_errno(report_fd, e as i32);
+        return;
+    }
+
+    if isolate.pid {
+        match unsafe { nix::unistd::fork() } {
+            Ok(nix::unistd::ForkResult::Parent { child, .. }) => {
+                // All reporting is done by this point (the grandchild never
+                // writes to report_fd itself) — close our copy now rather
+                // than at _exit, or the parent's blocking read of
+                // report_fd won't see EOF until the grandchild's entire
+                // run finishes, instead of as soon as it execs.
+                unsafe { nix::libc::close(report_fd) };
+
+                let mut status: i32 = 0;
+                unsafe { nix::libc::waitpid(child.as_raw(), &mut status, 0) };
+
+                // waitpid's status is a composite word, not a plain exit
+                // code — decode it properly so a nonzero exit or a signal
+                // isn't misreported as success.
+                let code = if nix::libc::WIFEXITED(status) {
+                    nix::libc::WEXITSTATUS(status)
+                } else if nix::libc::WIFSIGNALED(status) {
+                    128 + nix::libc::WTERMSIG(status)
+                } else {
+                    1
+                };
+                unsafe { nix::libc::_exit(code) };
+            }
+            Ok(nix::unistd::ForkResult::Child) => {
+                // We're now PID 1 of the new namespace; fall through and let
+                // `Command` exec the real command in us.
+            }
+            Err(e) => {
+                report_errno(report_fd, e as i32);
+                return;
+            }
+        }
+    }
+
+    if let Some(proc_mount) = proc_mount {
+        // Give the new mount namespace its own view of /proc.
+        unsafe {
+            nix::libc::mount(
+                proc_mount.source.as_ptr(),
+                proc_mount.target.as_ptr(),
+                proc_mount.fstype.as_ptr(),
+                0,
+                std::ptr::null(),
+            );
+        }
+    }
 }
 
-fn make_color(c: Color) -> ColorSpec {
-    let mut col = ColorSpec::new();
-    col.set_fg(Some(c));
-    col
+/// Creates a fresh cgroup v2 directory for the child and applies the
+/// `memory.max`/`cpu.max` limits from `isolate`.
+fn apply_cgroup_limits(name: &str, pid: u32, isolate: &Isolate) -> anyhow::Result<()> {
+    if isolate.memory_max.is_none() && isolate.cpu_max.is_none() {
+        return Ok(());
+    }
+
+    let dir = PathBuf::from(format!("/sys/fs/cgroup/shunt-{name}-{pid}"));
+    std::fs::create_dir(&dir).context("could not create cgroup directory")?;
+
+    if let Some(max) = &isolate.memory_max {
+        std::fs::write(dir.join("memory.max"), max).context("could not set memory.max")?;
+    }
+    if let Some(max) = &isolate.cpu_max {
+        std::fs::write(dir.join("cpu.max"), max).context("could not set cpu.max")?;
+    }
+
+    std::fs::write(dir.join("cgroup.procs"), pid.to_string())
+        .context("could not move process into its cgroup")?;
+
+    Ok(())
 }
 
-fn start_command(name: &str, cmd_config: &ShuntCommand) -> anyhow::Result<Handle> {
+fn start_command(
+    info: &CommandInfo,
+    cmd_config: &ShuntCommand,
+    jobserver: Option<&Jobserver>,
+    sink: &Arc<dyn EventSink>,
+) -> anyhow::Result<Handle> {
+    let name = &info.name;
     // Are *we* attached to a TTY?
     let our_stdout = std::io::stdout().as_raw_fd();
     let is_tty = nix::unistd::isatty(our_stdout).unwrap();
@@ -165,19 +944,74 @@ fn start_command(name: &str, cmd_config: &ShuntCommand) -> anyhow::Result<Handle
         cmd.env_remove(k);
     }
 
+    // Let recursive make/cargo invocations draw from the same token pool
+    // instead of each spawning their own unlimited workers.
+    if let Some(js) = jobserver {
+        cmd.env("MAKEFLAGS", &js.makeflags);
+    }
+
+    let mut isolate_report_fds = None;
+
+    if let Some(isolate) = cmd_config.isolate.clone() {
+        let mut fds = [-1i32; 2];
+        if unsafe { nix::libc::pipe(&mut fds[0]) } != 0 {
+            anyhow::bail!("could not create isolation report pipe");
+        }
+        let (report_read, report_write) = (fds[0], fds[1]);
+        // The write end must survive up to (and be usable during) `exec`'s
+        // own fork, but never leak into the exec'd program's open files.
+        unsafe {
+            nix::libc::fcntl(report_write, nix::libc::F_SETFD, nix::libc::FD_CLOEXEC);
+        }
+        isolate_report_fds = Some((report_read, report_write));
+
+        let proc_mount = isolate.mount.then(ProcMount::new);
+
+        unsafe {
+            cmd.pre_exec(move || {
+                unshare_namespaces(&isolate, proc_mount.as_ref(), report_write);
+                Ok(())
+            });
+        }
+    }
+
     let cmd = cmd
         .spawn()
         .context(format!("command \"{}\" failed to spawn", name))?;
 
+    if let Some((report_read, report_write)) = isolate_report_fds {
+        // We still hold our own copy of the write end (fds survive fork);
+        // close it or a successful, warning-free `read` below would block
+        // forever waiting for an EOF that never comes.
+        unsafe {
+            nix::libc::close(report_write);
+        }
+
+        let mut bytes = [0u8; 4];
+        let n =
+            unsafe { nix::libc::read(report_read, bytes.as_mut_ptr() as *mut _, bytes.len()) };
+        unsafe {
+            nix::libc::close(report_read);
+        }
+        if n == bytes.len() as isize {
+            let errno = i32::from_ne_bytes(bytes);
+            sink.message(
+                Some(name),
+                &format!(
+                    "warning: could not unshare namespaces (errno {errno}), running unsandboxed"
+                ),
+            );
+        }
+    }
+
+    if let Some(isolate) = &cmd_config.isolate {
+        if let Err(e) = apply_cgroup_limits(name, cmd.id(), isolate) {
+            sink.message(Some(name), &format!("could not apply cgroup limits: {e:?}"));
+        }
+    }
+
     Ok(Handle {
-        info: CommandInfo {
-            name: name.to_owned(),
-            color: if is_tty {
-                Some(pick_color(name, cmd_config))
-            } else {
-                None
-            },
-        },
+        info: info.clone(),
         child: cmd,
         tty_master: tty,
     })
@@ -185,6 +1019,16 @@ fn start_command(name: &str, cmd_config: &ShuntCommand) -> anyhow::Result<Handle
 
 static COLOR_CYCLE: AtomicU64 = AtomicU64::new(0);
 
+/// Picks the next color in the cycle for `name`, which its supervisor loop
+/// keeps across restarts, or `None` when our own output isn't a TTY.
+fn command_color(name: &str, cmd_config: &ShuntCommand) -> Option<ColorSpec> {
+    let our_stdout = std::io::stdout().as_raw_fd();
+    if !nix::unistd::isatty(our_stdout).unwrap() {
+        return None;
+    }
+    Some(pick_color(name, cmd_config))
+}
+
 fn pick_color(_: &str, _: &ShuntCommand) -> ColorSpec {
     let colors = [
         Color::Green,
@@ -195,7 +1039,7 @@ fn pick_color(_: &str, _: &ShuntCommand) -> ColorSpec {
     ];
 
     let i = COLOR_CYCLE.fetch_add(1, atomic::Ordering::Relaxed);
-    make_color(colors[i as usize])
+    make_color(colors[i as usize % colors.len()])
 }
 
 #[derive(Debug)]
@@ -204,3 +1048,56 @@ struct Handle {
     child: Child,
     tty_master: File,
 }
+
+#[cfg(test)]
+mod restart_tests {
+    use super::*;
+
+    #[test]
+    fn never_does_not_restart() {
+        assert!(!should_restart(RestartPolicy::Never, false, 1, None));
+        assert!(!should_restart(RestartPolicy::Never, true, 1, None));
+    }
+
+    #[test]
+    fn on_failure_restarts_only_on_failure() {
+        assert!(should_restart(RestartPolicy::OnFailure, false, 1, None));
+        assert!(!should_restart(RestartPolicy::OnFailure, true, 1, None));
+    }
+
+    #[test]
+    fn always_restarts_regardless_of_success() {
+        assert!(should_restart(RestartPolicy::Always, true, 1, None));
+        assert!(should_restart(RestartPolicy::Always, false, 1, None));
+    }
+
+    #[test]
+    fn max_retries_stops_restarts_once_reached() {
+        assert!(should_restart(RestartPolicy::Always, false, 2, Some(3)));
+        assert!(!should_restart(RestartPolicy::Always, false, 3, Some(3)));
+        assert!(!should_restart(RestartPolicy::Always, false, 4, Some(3)));
+    }
+
+    #[test]
+    fn backoff_delay_doubles_each_attempt_until_ceiling() {
+        let backoff = Backoff {
+            base_ms: 100,
+            ceiling_ms: 1000,
+        };
+        assert_eq!(backoff_delay(&backoff, 1), Duration::from_millis(100));
+        assert_eq!(backoff_delay(&backoff, 2), Duration::from_millis(200));
+        assert_eq!(backoff_delay(&backoff, 3), Duration::from_millis(400));
+        assert_eq!(backoff_delay(&backoff, 4), Duration::from_millis(800));
+        assert_eq!(backoff_delay(&backoff, 5), Duration::from_millis(1000));
+        assert_eq!(backoff_delay(&backoff, 100), Duration::from_millis(1000));
+    }
+
+    #[test]
+    fn backoff_delay_does_not_overflow_on_large_attempts() {
+        let backoff = Backoff {
+            base_ms: 500,
+            ceiling_ms: 30_000,
+        };
+        assert_eq!(backoff_delay(&backoff, u32::MAX), Duration::from_millis(30_000));
+    }
+}
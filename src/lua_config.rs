@@ -0,0 +1,214 @@
+use std::{cell::RefCell, collections::HashMap, path::PathBuf, rc::Rc};
+
+use anyhow::Context;
+use mlua::{Lua, Table};
+
+use crate::shunt::{
+    AutoBool, Backoff, Compression, Isolate, LogFile, OutputFormat, ReadyWhen, RestartPolicy, Shunt,
+    ShuntCommand,
+};
+
+/// Executes a `.lua` config script and collects the commands it registers
+/// through the `shunt.command(name, opts)` API into a `Shunt`. Unlike the
+/// static JSON5 format, a script can loop, branch on the host OS, or glob
+/// the filesystem to build up a variable number of commands.
+pub fn load(source: &str) -> anyhow::Result<Shunt> {
+    let lua = Lua::new();
+    let commands = Rc::new(RefCell::new(HashMap::new()));
+
+    let shunt_table = lua.create_table()?;
+
+    let commands_for_command = Rc::clone(&commands);
+    let command_fn = lua.create_function(move |_, (name, opts): (String, Table)| {
+        let cmd = table_to_command(&opts).map_err(mlua::Error::external)?;
+        commands_for_command.borrow_mut().insert(name, cmd);
+        Ok(())
+    })?;
+    shunt_table.set("command", command_fn)?;
+
+    let env_fn = lua.create_function(|_, name: String| Ok(std::env::var(name).ok()))?;
+    shunt_table.set("env", env_fn)?;
+
+    let glob_fn = lua.create_function(|_, pattern: String| {
+        let paths: Vec<String> = glob::glob(&pattern)
+            .map_err(mlua::Error::external)?
+            .filter_map(Result::ok)
+            .map(|p| p.to_string_lossy().into_owned())
+            .collect();
+        Ok(paths)
+    })?;
+    shunt_table.set("glob", glob_fn)?;
+
+    lua.globals().set("shunt", shunt_table)?;
+
+    lua.load(source)
+        .exec()
+        .context("lua config script raised an error")?;
+
+    drop(lua);
+
+    Ok(Shunt {
+        commands: Rc::try_unwrap(commands)
+            .expect("no outstanding references to the command table after the script finished")
+            .into_inner(),
+        concurrency: None,
+        control: None,
+        output: OutputFormat::default(),
+    })
+}
+
+/// Reads the opts table passed to `shunt.command(name, opts)`, mirroring the
+/// `argv`/`workdir`/`env`/`tty` shape accepted by the JSON5 "full" command
+/// form.
+fn table_to_command(opts: &Table) -> anyhow::Result<ShuntCommand> {
+    let argv: Vec<String> = opts
+        .get("argv")
+        .context("shunt.command opts is missing \"argv\"")?;
+
+    let cwd = std::env::current_dir().context("could not access current working directory")?;
+    let workdir = match opts.get::<_, Option<String>>("workdir")? {
+        Some(w) => cwd.join(w),
+        None => cwd,
+    };
+
+    let tty = match opts.get::<_, Option<String>>("tty")?.as_deref() {
+        Some("always") => AutoBool::Always,
+        Some("never") => AutoBool::Never,
+        _ => AutoBool::Auto,
+    };
+
+    let mut env = HashMap::new();
+    if let Some(env_table) = opts.get::<_, Option<Table>>("env")? {
+        for pair in env_table.pairs::<String, Option<String>>() {
+            let (k, v) = pair?;
+            env.insert(k, v);
+        }
+    }
+
+    let depends_on: Vec<String> = opts.get::<_, Option<Vec<String>>>("depends_on")?.unwrap_or_default();
+
+    let ready_when = opts
+        .get::<_, Option<Table>>("ready_when")?
+        .map(|t| table_to_ready_when(&t))
+        .transpose()?;
+
+    let restart = opts
+        .get::<_, Option<String>>("restart")?
+        .map(|s| table_to_restart(&s))
+        .transpose()?
+        .unwrap_or_default();
+
+    let max_retries: Option<u32> = opts.get("max_retries")?;
+
+    let backoff = opts
+        .get::<_, Option<Table>>("backoff")?
+        .map(|t| table_to_backoff(&t))
+        .transpose()?
+        .unwrap_or_default();
+
+    let weight: u32 = opts.get::<_, Option<u32>>("weight")?.unwrap_or(1);
+
+    let isolate = opts
+        .get::<_, Option<Table>>("isolate")?
+        .map(|t| table_to_isolate(&t))
+        .transpose()?;
+
+    let log = opts
+        .get::<_, Option<Table>>("log")?
+        .map(|t| table_to_log(&t))
+        .transpose()?;
+
+    Ok(ShuntCommand {
+        argv,
+        workdir,
+        tty,
+        env,
+        depends_on,
+        ready_when,
+        restart,
+        max_retries,
+        backoff,
+        weight,
+        isolate,
+        log,
+    })
+}
+
+/// Reads a `ready_when = { kind = "exit" | "log-match" | "port", ... }`
+/// table, mirroring the JSON5 `ready_when`'s `kind`-tagged shape.
+fn table_to_ready_when(t: &Table) -> anyhow::Result<ReadyWhen> {
+    let kind: String = t
+        .get("kind")
+        .context("ready_when is missing \"kind\"")?;
+    Ok(match kind.as_str() {
+        "exit" => ReadyWhen::Exit,
+        "log-match" => ReadyWhen::LogMatch {
+            pattern: t
+                .get("pattern")
+                .context("ready_when kind \"log-match\" is missing \"pattern\"")?,
+        },
+        "port" => ReadyWhen::Port {
+            port: t
+                .get("port")
+                .context("ready_when kind \"port\" is missing \"port\"")?,
+        },
+        other => anyhow::bail!("unknown ready_when kind \"{other}\""),
+    })
+}
+
+/// Reads a `restart = "never" | "on-failure" | "always"` string, mirroring
+/// the JSON5 `RestartPolicy` spelling.
+fn table_to_restart(s: &str) -> anyhow::Result<RestartPolicy> {
+    Ok(match s {
+        "never" => RestartPolicy::Never,
+        "on-failure" => RestartPolicy::OnFailure,
+        "always" => RestartPolicy::Always,
+        other => anyhow::bail!("unknown restart policy \"{other}\""),
+    })
+}
+
+/// Reads a `backoff = { base_ms = ..., ceiling_ms = ... }` table, defaulting
+/// each field independently the same way the JSON5 format does.
+fn table_to_backoff(t: &Table) -> anyhow::Result<Backoff> {
+    let default = Backoff::default();
+    Ok(Backoff {
+        base_ms: t.get::<_, Option<u64>>("base_ms")?.unwrap_or(default.base_ms),
+        ceiling_ms: t
+            .get::<_, Option<u64>>("ceiling_ms")?
+            .unwrap_or(default.ceiling_ms),
+    })
+}
+
+/// Reads an `isolate = { pid = ..., mount = ..., net = ..., uts = ...,
+/// memory_max = ..., cpu_max = ... }` table.
+fn table_to_isolate(t: &Table) -> anyhow::Result<Isolate> {
+    Ok(Isolate {
+        pid: t.get::<_, Option<bool>>("pid")?.unwrap_or(false),
+        mount: t.get::<_, Option<bool>>("mount")?.unwrap_or(false),
+        net: t.get::<_, Option<bool>>("net")?.unwrap_or(false),
+        uts: t.get::<_, Option<bool>>("uts")?.unwrap_or(false),
+        memory_max: t.get("memory_max")?,
+        cpu_max: t.get("cpu_max")?,
+    })
+}
+
+/// Reads a `log = { path = ..., max_size = ..., max_files = ..., compress =
+/// ... }` table, mirroring the JSON5 `LogFile` shape.
+fn table_to_log(t: &Table) -> anyhow::Result<LogFile> {
+    let path: String = t.get("path").context("log is missing \"path\"")?;
+    let compress = match t.get::<_, Option<String>>("compress")?.as_deref() {
+        Some("gzip") => Compression::Gzip,
+        Some("bzip2") => Compression::Bzip2,
+        _ => Compression::None,
+    };
+    Ok(LogFile {
+        path: PathBuf::from(path),
+        max_size: t
+            .get::<_, Option<u64>>("max_size")?
+            .unwrap_or_else(LogFile::default_max_size),
+        max_files: t
+            .get::<_, Option<u32>>("max_files")?
+            .unwrap_or_else(LogFile::default_max_files),
+        compress,
+    })
+}
@@ -0,0 +1,153 @@
+use std::io::Write;
+
+use termcolor::{ColorChoice, ColorSpec, StandardStream, StandardStreamLock, WriteColor};
+
+use crate::shunt::OutputFormat;
+
+/// Identifies a single command for display purposes: its name and, when our
+/// own output is a TTY, a color picked when its supervisor loop starts and
+/// kept across restart-policy relaunches. A control-socket `start` or
+/// `restart` begins a new supervisor loop and so picks a new color.
+#[derive(Debug, Clone)]
+pub struct CommandInfo {
+    pub name: String,
+    pub color: Option<ColorSpec>,
+}
+
+/// Every command's stdout and stderr are merged into a single stream before
+/// shunt ever sees them (see `start_command`), so this is always `Stdout`
+/// for now — the tag exists so the NDJSON shape doesn't need to change if
+/// that's ever split out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+}
+
+/// Everything shunt prints about itself — a command's output lines, exits,
+/// restarts, and other lifecycle messages — passes through here, so the
+/// human-readable and NDJSON renderings can't drift out of sync with each
+/// other.
+pub trait EventSink: Send + Sync {
+    /// One decoded line of a command's (merged) output.
+    fn line(&self, info: &CommandInfo, stream: Stream, text: &str);
+    /// A command's process exited. `detail` is a human-readable summary
+    /// ("finished: exit status: 0", "failed to be waited on: ..."),
+    /// `code` its numeric exit code when one exists.
+    fn exit(&self, command: &str, detail: &str, code: Option<i32>, success: bool);
+    /// A command is being relaunched after exiting, per its restart policy.
+    fn restarting(&self, info: &CommandInfo, attempt: u32);
+    /// Any other lifecycle or error message not tied to a specific output
+    /// line, e.g. a signal being received or a command failing to spawn.
+    /// `command` is `None` for messages that aren't about one command.
+    fn message(&self, command: Option<&str>, text: &str);
+}
+
+pub fn sink_for(format: OutputFormat) -> Box<dyn EventSink> {
+    match format {
+        OutputFormat::Human => Box::new(HumanSink),
+        OutputFormat::Json => Box::new(JsonSink),
+    }
+}
+
+/// Shunt's original behaviour: colored, `[name] text`-prefixed lines on
+/// stdout.
+struct HumanSink;
+
+impl EventSink for HumanSink {
+    fn line(&self, info: &CommandInfo, _stream: Stream, text: &str) {
+        let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+        prefix_write(&mut stdout, info, text);
+    }
+
+    fn exit(&self, command: &str, detail: &str, _code: Option<i32>, _success: bool) {
+        println!("{command} {detail}");
+    }
+
+    fn restarting(&self, info: &CommandInfo, attempt: u32) {
+        let mut stdout = StandardStream::stdout(ColorChoice::Auto);
+        prefix_write(&mut stdout, info, &format!("restarting (attempt {attempt})"));
+    }
+
+    fn message(&self, _command: Option<&str>, text: &str) {
+        println!("{text}");
+    }
+}
+
+fn colored_write(stdout: &mut StandardStreamLock, color: &Option<ColorSpec>, s: &str) {
+    if let Some(color) = color {
+        stdout.set_color(color).unwrap();
+    }
+    write!(stdout, "{}", s).unwrap();
+    if color.is_some() {
+        stdout.reset().unwrap();
+    }
+}
+
+fn prefix_write(stream: &mut StandardStream, info: &CommandInfo, s: &str) {
+    let mut stream = stream.lock();
+    colored_write(&mut stream, &info.color, &format!("[{}] ", &info.name));
+    writeln!(&mut stream, "{}", s).unwrap();
+}
+
+/// One JSON object per event on stdout, for editors, CI dashboards, and log
+/// shippers to consume programmatically.
+struct JsonSink;
+
+impl JsonSink {
+    fn emit(&self, value: serde_json::Value) {
+        println!("{value}");
+    }
+}
+
+impl EventSink for JsonSink {
+    fn line(&self, info: &CommandInfo, stream: Stream, text: &str) {
+        self.emit(serde_json::json!({
+            "ts": now_millis(),
+            "command": info.name,
+            "stream": stream_name(stream),
+            "line": text,
+        }));
+    }
+
+    fn exit(&self, command: &str, detail: &str, code: Option<i32>, success: bool) {
+        self.emit(serde_json::json!({
+            "ts": now_millis(),
+            "event": "exit",
+            "command": command,
+            "status": code,
+            "success": success,
+            "detail": detail,
+        }));
+    }
+
+    fn restarting(&self, info: &CommandInfo, attempt: u32) {
+        self.emit(serde_json::json!({
+            "ts": now_millis(),
+            "event": "restart",
+            "command": info.name,
+            "attempt": attempt,
+        }));
+    }
+
+    fn message(&self, command: Option<&str>, text: &str) {
+        self.emit(serde_json::json!({
+            "ts": now_millis(),
+            "event": "message",
+            "command": command,
+            "text": text,
+        }));
+    }
+}
+
+fn stream_name(stream: Stream) -> &'static str {
+    match stream {
+        Stream::Stdout => "stdout",
+    }
+}
+
+fn now_millis() -> u128 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0)
+}
@@ -1,10 +1,36 @@
 use std::{collections::HashMap, ffi::OsString, path::PathBuf};
 
+use anyhow::Context;
+use regex::Regex;
 use serde::Deserialize;
 
 #[derive(Debug, Deserialize)]
 pub struct Shunt {
     pub commands: HashMap<String, ShuntCommand>,
+    /// Maximum number of command "weights" allowed to run at once, enforced
+    /// via a GNU make compatible jobserver. `None` means unlimited.
+    #[serde(default)]
+    pub concurrency: Option<u32>,
+    /// Path to a Unix domain socket to open for the `list`/`stop`/`start`/
+    /// `restart`/`logs` control protocol. Can be overridden by `--control`.
+    #[serde(default)]
+    pub control: Option<PathBuf>,
+    /// How shunt renders its own output and lifecycle messages. Can be
+    /// overridden by `--format`.
+    #[serde(default)]
+    pub output: OutputFormat,
+}
+
+/// How shunt renders its own output (command output lines, exit events,
+/// restarts, signals) rather than the commands' own behaviour.
+#[derive(Debug, Clone, Copy, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum OutputFormat {
+    /// Colored, `[name] line`-prefixed text on stdout.
+    #[default]
+    Human,
+    /// One JSON object per line on stdout, for machine consumption.
+    Json,
 }
 
 #[derive(Debug, Clone)]
@@ -13,6 +39,121 @@ pub struct ShuntCommand {
     pub workdir: PathBuf,
     pub tty: AutoBool,
     pub env: HashMap<String, Option<String>>,
+    pub depends_on: Vec<String>,
+    pub ready_when: Option<ReadyWhen>,
+    pub restart: RestartPolicy,
+    pub max_retries: Option<u32>,
+    pub backoff: Backoff,
+    pub weight: u32,
+    pub isolate: Option<Isolate>,
+    pub log: Option<LogFile>,
+}
+
+/// Tees a command's merged output to a file on disk, rotating it once it
+/// passes `max_size` and keeping at most `max_files` rotated segments.
+#[derive(Debug, Clone, Deserialize)]
+pub struct LogFile {
+    pub path: PathBuf,
+    #[serde(default = "LogFile::default_max_size")]
+    pub max_size: u64,
+    #[serde(default = "LogFile::default_max_files")]
+    pub max_files: u32,
+    #[serde(default)]
+    pub compress: Compression,
+}
+
+impl LogFile {
+    pub(crate) fn default_max_size() -> u64 {
+        10 * 1024 * 1024
+    }
+
+    pub(crate) fn default_max_files() -> u32 {
+        5
+    }
+}
+
+/// How a rotated log segment should be compressed, if at all.
+#[derive(Debug, Clone, Copy, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum Compression {
+    #[default]
+    None,
+    Gzip,
+    Bzip2,
+}
+
+/// Linux namespace and cgroup v2 sandboxing for a single command, opt-in per
+/// namespace kind.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct Isolate {
+    #[serde(default)]
+    pub pid: bool,
+    #[serde(default)]
+    pub mount: bool,
+    #[serde(default)]
+    pub net: bool,
+    #[serde(default)]
+    pub uts: bool,
+    /// Passed verbatim to the cgroup's `memory.max`, e.g. `"512M"`.
+    pub memory_max: Option<String>,
+    /// Passed verbatim to the cgroup's `cpu.max`, e.g. `"50000 100000"`.
+    pub cpu_max: Option<String>,
+}
+
+/// Whether a command should be relaunched after it exits.
+#[derive(Debug, Clone, Copy, Deserialize, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    #[default]
+    Never,
+    OnFailure,
+    Always,
+}
+
+/// Capped exponential backoff between restart attempts: `base_ms * 2^(n-1)`,
+/// clamped to `ceiling_ms`.
+#[derive(Debug, Clone, Copy, Deserialize)]
+pub struct Backoff {
+    #[serde(default = "Backoff::default_base_ms")]
+    pub base_ms: u64,
+    #[serde(default = "Backoff::default_ceiling_ms")]
+    pub ceiling_ms: u64,
+}
+
+impl Backoff {
+    fn default_base_ms() -> u64 {
+        500
+    }
+
+    fn default_ceiling_ms() -> u64 {
+        30_000
+    }
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            base_ms: Self::default_base_ms(),
+            ceiling_ms: Self::default_ceiling_ms(),
+        }
+    }
+}
+
+/// How a dependant decides that this command has become ready, i.e. that it
+/// is safe to start commands which `depends_on` it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "kebab-case", tag = "kind")]
+pub enum ReadyWhen {
+    /// The command is ready once it exits with status 0. The natural choice
+    /// for oneshot setup tasks such as migrations.
+    Exit,
+    /// The command is ready once a line matching `pattern` appears on its
+    /// (prefixed) output.
+    LogMatch { pattern: String },
+    /// The command is ready once `port` accepts TCP connections on
+    /// localhost.
+    Port { port: u16 },
 }
 
 impl<'de> Deserialize<'de> for ShuntCommand {
@@ -30,6 +171,18 @@ impl<'de> Deserialize<'de> for ShuntCommand {
                 workdir: Option<PathBuf>,
                 #[serde(default)]
                 env: HashMap<String, Option<String>>,
+                #[serde(default)]
+                depends_on: Vec<String>,
+                ready_when: Option<ReadyWhen>,
+                #[serde(default)]
+                restart: RestartPolicy,
+                max_retries: Option<u32>,
+                #[serde(default)]
+                backoff: Backoff,
+                #[serde(default = "ShuntCommand::default_weight")]
+                weight: u32,
+                isolate: Option<Isolate>,
+                log: Option<LogFile>,
             },
         }
 
@@ -50,22 +203,52 @@ impl<'de> Deserialize<'de> for ShuntCommand {
                 tty: AutoBool::Auto,
                 workdir: cwd,
                 env: Default::default(),
+                depends_on: Vec::new(),
+                ready_when: None,
+                restart: RestartPolicy::Never,
+                max_retries: None,
+                backoff: Backoff::default(),
+                weight: Self::default_weight(),
+                isolate: None,
+                log: None,
             },
             CommandConf::Full {
                 argv,
                 tty,
                 workdir,
                 env,
+                depends_on,
+                ready_when,
+                restart,
+                max_retries,
+                backoff,
+                weight,
+                isolate,
+                log,
             } => ShuntCommand {
                 argv,
                 tty: tty.unwrap_or(AutoBool::Auto),
                 workdir: cwd.join(workdir.unwrap_or_else(|| PathBuf::from("."))),
                 env,
+                depends_on,
+                ready_when,
+                restart,
+                max_retries,
+                backoff,
+                weight,
+                isolate,
+                log,
             },
         })
     }
 }
 
+impl ShuntCommand {
+    fn default_weight() -> u32 {
+        1
+    }
+}
+
 #[derive(Debug, Clone, Copy, Deserialize)]
 #[serde(rename_all = "kebab-case")]
 pub enum AutoBool {
@@ -73,3 +256,135 @@ pub enum AutoBool {
     Always,
     Never,
 }
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Mark {
+    Visiting,
+    Visited,
+}
+
+impl Shunt {
+    /// Returns command names ordered so that every command appears after
+    /// everything in its `depends_on`. Errors if the dependency graph
+    /// contains a cycle or refers to a command that isn't defined, so that
+    /// bad configs are rejected before anything is spawned.
+    pub fn dependency_order(&self) -> anyhow::Result<Vec<String>> {
+        let mut marks: HashMap<&str, Mark> = HashMap::new();
+        let mut order = Vec::with_capacity(self.commands.len());
+
+        for name in self.commands.keys() {
+            self.visit(name, &mut marks, &mut order)?;
+        }
+
+        Ok(order)
+    }
+
+    /// Rejects any `ready_when: {kind: log-match, pattern: ...}` whose
+    /// pattern isn't a valid regex, so a typo is caught up front rather than
+    /// panicking in the supervisor thread after the command has already been
+    /// spawned.
+    pub fn validate_ready_when(&self) -> anyhow::Result<()> {
+        for (name, cmd) in &self.commands {
+            if let Some(ReadyWhen::LogMatch { pattern }) = &cmd.ready_when {
+                Regex::new(pattern).with_context(|| {
+                    format!("command \"{name}\" has an invalid ready-when pattern \"{pattern}\"")
+                })?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn visit<'a>(
+        &'a self,
+        name: &'a str,
+        marks: &mut HashMap<&'a str, Mark>,
+        order: &mut Vec<String>,
+    ) -> anyhow::Result<()> {
+        match marks.get(name) {
+            Some(Mark::Visited) => return Ok(()),
+            Some(Mark::Visiting) => {
+                anyhow::bail!("dependency cycle detected involving command \"{name}\"")
+            }
+            None => {}
+        }
+
+        let cmd = self
+            .commands
+            .get(name)
+            .with_context(|| format!("a command depends on \"{name}\", which isn't defined"))?;
+
+        marks.insert(name, Mark::Visiting);
+        for dep in &cmd.depends_on {
+            self.visit(dep, marks, order)?;
+        }
+        marks.insert(name, Mark::Visited);
+        order.push(name.to_owned());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn command(depends_on: &[&str]) -> ShuntCommand {
+        ShuntCommand {
+            argv: vec!["true".to_owned()],
+            workdir: PathBuf::from("."),
+            tty: AutoBool::Auto,
+            env: HashMap::new(),
+            depends_on: depends_on.iter().map(|s| s.to_string()).collect(),
+            ready_when: None,
+            restart: RestartPolicy::Never,
+            max_retries: None,
+            backoff: Backoff::default(),
+            weight: 1,
+            isolate: None,
+            log: None,
+        }
+    }
+
+    fn shunt(commands: &[(&str, &[&str])]) -> Shunt {
+        Shunt {
+            commands: commands
+                .iter()
+                .map(|(name, deps)| (name.to_string(), command(deps)))
+                .collect(),
+            concurrency: None,
+            control: None,
+            output: OutputFormat::default(),
+        }
+    }
+
+    #[test]
+    fn dependency_order_respects_deps() {
+        let s = shunt(&[("web", &["db"]), ("db", &[])]);
+        let order = s.dependency_order().unwrap();
+        let db = order.iter().position(|n| n == "db").unwrap();
+        let web = order.iter().position(|n| n == "web").unwrap();
+        assert!(db < web);
+    }
+
+    #[test]
+    fn dependency_order_detects_direct_cycle() {
+        let s = shunt(&[("a", &["b"]), ("b", &["a"])]);
+        let err = s.dependency_order().unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+    }
+
+    #[test]
+    fn dependency_order_detects_self_cycle() {
+        let s = shunt(&[("a", &["a"])]);
+        let err = s.dependency_order().unwrap_err();
+        assert!(err.to_string().contains("cycle"));
+    }
+
+    #[test]
+    fn dependency_order_rejects_missing_dependency() {
+        let s = shunt(&[("web", &["db"])]);
+        let err = s.dependency_order().unwrap_err();
+        assert!(err.to_string().contains("isn't defined"));
+    }
+}